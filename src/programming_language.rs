@@ -3,7 +3,7 @@ use std::fs;
 use serde::Deserialize;
 use toml;
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum VariableNameFormat {
     SnakeCase,
@@ -11,7 +11,7 @@ pub enum VariableNameFormat {
     PascalCase,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct ProgrammingLanguage {
     pub name: String,
     pub variable_format: VariableNameFormat,
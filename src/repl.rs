@@ -0,0 +1,51 @@
+use std::io::{self, Write};
+
+use crate::stub::language::Language;
+use crate::stub::parser::{highlight_error, parse_generator_stub, StubError};
+use crate::stub::renderer::render_stub;
+
+/// Runs an interactive loop: read a generator (possibly spanning several
+/// lines), render it against `lang` on every attempt, and print the result.
+/// A line is buffered rather than submitted immediately when the statement
+/// so far is incomplete (e.g. a `loop { ... }` block whose closing `}`
+/// hasn't been typed yet), so multi-line commands can be entered naturally.
+/// Exits on EOF (Ctrl-D).
+pub fn run(lang: Language) {
+    let stdin = io::stdin();
+    let mut buffer = String::new();
+
+    loop {
+        print!("{} ", if buffer.is_empty() { ">>" } else { ".." });
+        io::stdout().flush().ok();
+
+        let mut line = String::new();
+        if stdin.read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+
+        buffer.push_str(&line);
+
+        match parse_generator_stub(&buffer) {
+            Ok(stub) => {
+                println!("{}", render_stub(lang.clone(), stub));
+                buffer.clear();
+            }
+            Err(errors) if needs_more_input(&errors, &buffer) => continue,
+            Err(errors) => {
+                for error in &errors {
+                    eprintln!("{}", highlight_error(&buffer, error));
+                }
+                buffer.clear();
+            }
+        }
+    }
+}
+
+/// A `{ ... }` loop block missing its closing `}` reports a single error
+/// pointing at the end of what's been typed so far; treat that (and only
+/// that) as "the user isn't done yet" rather than a real mistake to report.
+/// A `loop` whose count is followed by a line end (`loop n` then Enter) is
+/// not buffered: the parser reports the line end itself as a missing body.
+fn needs_more_input(errors: &[StubError], buffer: &str) -> bool {
+    matches!(errors, [error] if error.span.start == buffer.len())
+}
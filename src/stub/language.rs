@@ -1,8 +1,7 @@
 use std::fs;
+use std::path::{Path, PathBuf};
 
 use anyhow::{anyhow, Context, Result};
-use lazy_static::lazy_static;
-use regex::Regex;
 use serde::{Deserialize, Serialize};
 use include_dir::include_dir;
 use tera::Tera;
@@ -11,11 +10,10 @@ use crate::stub::VariableCommand;
 
 const HARDCODED_TEMPLATE_DIR: include_dir::Dir<'static> = include_dir!("$CARGO_MANIFEST_DIR/config/stub_templates");
 
-lazy_static! {
-    static ref SC_WORD_BREAK: Regex = Regex::new(r"([a-z])([A-Z])").unwrap();
-    static ref PC_WORD_BREAK: Regex = Regex::new(r"([A-Z]*)([A-Z][a-z])").unwrap();
-    static ref PC_WORD_END: Regex = Regex::new(r"([A-Z])([A-Z]*$)").unwrap();
-}
+/// Points at a directory of user-supplied `<language>/stub_config.toml` +
+/// `*.jinja` folders, checked before the embedded languages in
+/// [`TryFrom<&str>`] so templates can be tweaked or added without forking.
+const EXTERNAL_LANG_DIR_ENV_VAR: &str = "COCTUS_LANG_DIR";
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "snake_case")]
@@ -28,40 +26,67 @@ pub enum VariableNameFormat {
 
 impl VariableNameFormat {
     pub fn convert(&self, variable_name: &str) -> String {
+        let words = Self::segment_words(variable_name);
+
         match self {
-            Self::SnakeCase => Self::convert_to_snake_case(variable_name),
-            Self::PascalCase => Self::covert_to_pascal_case(variable_name),
-            Self::CamelCase => Self::covert_to_camel_case(variable_name),
+            Self::SnakeCase => words.join("_"),
+            Self::PascalCase => words.iter().map(|word| Self::capitalize(word)).collect(),
+            Self::CamelCase => words.iter().enumerate()
+                .map(|(i, word)| if i == 0 { word.clone() } else { Self::capitalize(word) })
+                .collect(),
         }
     }
 
-    fn convert_to_snake_case(variable_name: &str) -> String {
-        SC_WORD_BREAK
-            .replace_all(variable_name, |caps: &regex::Captures| {
-                format!("{}_{}", &caps[1], &caps[2].to_lowercase())
-            })
-            .to_lowercase()
-            .to_string()
-    }
+    /// Splits an identifier into lowercased word tokens, cutting a boundary:
+    /// - at every run of `_`, `-`, or space separators
+    /// - at a lowercase/digit -> uppercase transition (`fooBar` -> `foo|Bar`)
+    /// - inside an uppercase run, right before an uppercase-then-lowercase
+    ///   letter (`HTTPServer` -> `HTTP|Server`)
+    /// - between a letter and a following digit (`value2d` -> `value|2d`)
+    ///
+    /// This replaces a trio of regexes that mislabeled consecutive capitals
+    /// and couldn't tell camelCase from PascalCase input.
+    fn segment_words(variable_name: &str) -> Vec<String> {
+        let chars: Vec<char> = variable_name.chars().collect();
+        let mut words = Vec::new();
+        let mut current = String::new();
+
+        for (i, &c) in chars.iter().enumerate() {
+            if c == '_' || c == '-' || c == ' ' {
+                if !current.is_empty() {
+                    words.push(std::mem::take(&mut current));
+                }
+                continue;
+            }
 
-    fn covert_to_pascal_case(variable_name: &str) -> String {
-        variable_name[0..1].to_uppercase() + &Self::pascalize(&variable_name[1..])
-    }
+            if let Some(&prev) = chars.get(i.wrapping_sub(1)).filter(|_| i > 0) {
+                let next = chars.get(i + 1).copied();
 
-    fn covert_to_camel_case(variable_name: &str) -> String {
-        variable_name[0..1].to_lowercase() + &Self::pascalize(&variable_name[1..])
-    }
+                let is_new_word_boundary = (!prev.is_uppercase() && c.is_uppercase())
+                    || (prev.is_uppercase() && c.is_uppercase() && next.is_some_and(char::is_lowercase))
+                    || (prev.is_alphabetic() && c.is_ascii_digit());
 
-    fn pascalize(variable_slice: &str) -> String {
-        let start_replaced = PC_WORD_BREAK.replace_all(variable_slice, |caps: &regex::Captures| {
-            format!("{}{}", &caps[1].to_lowercase(), &caps[2])
-        });
+                if is_new_word_boundary && !current.is_empty() {
+                    words.push(std::mem::take(&mut current));
+                }
+            }
+
+            current.push(c);
+        }
+
+        if !current.is_empty() {
+            words.push(current);
+        }
 
-        PC_WORD_END
-            .replace_all(&start_replaced, |caps: &regex::Captures| {
-                format!("{}{}", &caps[1], &caps[2].to_lowercase())
-            })
-            .to_string()
+        words.iter().map(|word| word.to_lowercase()).collect()
+    }
+
+    fn capitalize(word: &str) -> String {
+        let mut chars = word.chars();
+        match chars.next() {
+            Some(first) => first.to_uppercase().chain(chars).collect(),
+            None => String::new(),
+        }
     }
 }
 
@@ -130,8 +155,17 @@ impl TryFrom<&str> for Language {
     type Error = anyhow::Error;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        Self::find_hardcoded_lang_by_name(&value.to_lowercase())?
-            .or(Self::find_hardcoded_lang_by_alias(&value.to_lowercase()))
+        let name = value.to_lowercase();
+
+        if let Some(lang) = Self::find_external_lang_by_name(&name)? {
+            return Ok(lang);
+        }
+        if let Some(lang) = Self::find_external_lang_by_alias(&name) {
+            return Ok(lang);
+        }
+
+        Self::find_hardcoded_lang_by_name(&name)?
+            .or_else(|| Self::find_hardcoded_lang_by_alias(&name))
             .ok_or(anyhow!("Unsupported language: {}", value))
     }
 }
@@ -197,32 +231,132 @@ impl Language {
         })
     }
 
-    fn find_lang_by_name<'a>(name: &'a str, lang_folders: &'a [String]) -> Result<Option<Language>> {
-        if lang_folders.iter().any(|l| l == name) {
-            let language_config_filepath = format!("config/stub_templates/{}/stub_config.toml", name);
-            let config_file_content = fs::read_to_string(language_config_filepath)
-                .context(format!("No stub configuration exists for {}", name))?;
+    /// Loads a single language from an on-disk directory containing a
+    /// `stub_config.toml` and its `*.jinja` templates, following the same
+    /// schema as the embedded languages. Set `COCTUS_LANG_DIR` to a directory
+    /// of such language folders to make them available to [`TryFrom<&str>`],
+    /// without recompiling the crate.
+    pub fn from_dir(path: &Path) -> Result<Self> {
+        let config_file_content = fs::read_to_string(path.join("stub_config.toml"))
+            .context(format!("No stub configuration exists at {}", path.display()))?;
 
-            Ok(toml::from_str(&config_file_content)
-                .context("There was an error loading the stub configuration")?)
-        } else {
-            Ok(None)
+        let mut lang: Self = toml::from_str(&config_file_content)
+            .context("There was an error loading the stub configuration")?;
+
+        let template_glob = path.join("*.jinja");
+        let template_glob = template_glob.to_str()
+            .context("Language directory path is not valid UTF-8")?;
+
+        lang.tera = Tera::new(template_glob)
+            .context("Failed to load templates from the language directory")?;
+
+        Ok(lang)
+    }
+
+    fn external_lang_dir() -> Option<PathBuf> {
+        std::env::var_os(EXTERNAL_LANG_DIR_ENV_VAR).map(PathBuf::from)
+    }
+
+    fn find_external_lang_by_name(name: &str) -> Result<Option<Language>> {
+        let Some(root) = Self::external_lang_dir() else { return Ok(None) };
+        let lang_dir = root.join(name);
+
+        if !lang_dir.join("stub_config.toml").is_file() {
+            return Ok(None);
         }
+
+        Self::from_dir(&lang_dir).map(Some)
     }
 
-    fn find_lang_by_alias<'a>(name: &'a str, lang_folders: &'a [String]) -> Option<Language> {
-        lang_folders
-            .iter()
-            .filter_map(|folder| {
-                let language_config_filepath = format!("config/stub_templates/{}/stub_config.toml", folder);
-                match fs::read_to_string(language_config_filepath) {
-                    Ok(config_file_content) => toml::from_str::<Language>(&config_file_content).ok(),
-                    _ => None,
-                }
-            })
-            .find(|l| match &l.aliases {
-                Some(aliases) => aliases.contains(&name.to_string()),
-                None => false,
-            })
+    fn find_external_lang_by_alias(name: &str) -> Option<Language> {
+        let root = Self::external_lang_dir()?;
+
+        fs::read_dir(root).ok()?
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter_map(|lang_dir| Self::from_dir(&lang_dir).ok())
+            .find(|lang| lang.aliases.as_ref().is_some_and(|aliases| aliases.contains(&name.to_string())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a minimal but complete language folder (config + every
+    /// template the renderer looks up) under `root/testlang`.
+    fn write_test_language(root: &Path) {
+        let dir = root.join("testlang");
+        fs::create_dir_all(&dir).unwrap();
+
+        fs::write(dir.join("stub_config.toml"), r#"
+            name = "testlang"
+            variable_format = "snake_case"
+            source_file_ext = "tl"
+            allow_uppercase_vars = false
+            keywords = []
+            aliases = []
+
+            [type_tokens]
+            Int = "Int"
+            Float = "Float"
+            Long = "Long"
+            Bool = "Bool"
+            Word = "Word"
+            String = "String"
+        "#).unwrap();
+
+        fs::write(dir.join("main.tl.jinja"), "BEGIN\n{% for c in commands %}{{ c }}{% endfor %}END").unwrap();
+        fs::write(dir.join("read.tl.jinja"), "READ\n").unwrap();
+        fs::write(dir.join("write.tl.jinja"), "WRITE\n").unwrap();
+        fs::write(dir.join("loop.tl.jinja"), "LOOP\n").unwrap();
+        fs::write(dir.join("loopline.tl.jinja"), "LOOPLINE\n").unwrap();
+    }
+
+    #[test]
+    fn renders_a_stub_end_to_end_using_a_language_from_coctus_lang_dir() {
+        let root = std::env::temp_dir().join(format!("coctus_lang_dir_test_{}", std::process::id()));
+        write_test_language(&root);
+        std::env::set_var(EXTERNAL_LANG_DIR_ENV_VAR, &root);
+
+        let lang = Language::try_from("testlang").expect("the external language should resolve");
+        let stub = crate::stub::parser::parse_generator_stub("read n:int\n")
+            .expect("a trivial generator should parse");
+        let rendered = crate::stub::renderer::render_stub(lang, stub);
+
+        std::env::remove_var(EXTERNAL_LANG_DIR_ENV_VAR);
+        fs::remove_dir_all(&root).ok();
+
+        assert!(rendered.contains("BEGIN"), "rendered output: {rendered}");
+        assert!(rendered.contains("READ"), "rendered output: {rendered}");
+        assert!(rendered.contains("END"), "rendered output: {rendered}");
+    }
+
+    #[test]
+    fn splits_an_uppercase_run_before_its_trailing_word() {
+        assert_eq!(VariableNameFormat::SnakeCase.convert("HTTPServer"), "http_server");
+        assert_eq!(VariableNameFormat::PascalCase.convert("HTTPServer"), "HttpServer");
+        assert_eq!(VariableNameFormat::CamelCase.convert("HTTPServer"), "httpServer");
+    }
+
+    #[test]
+    fn splits_on_lowercase_to_uppercase_transitions() {
+        assert_eq!(VariableNameFormat::SnakeCase.convert("fooBar"), "foo_bar");
+        assert_eq!(VariableNameFormat::PascalCase.convert("fooBar"), "FooBar");
+    }
+
+    #[test]
+    fn splits_a_letter_from_a_following_digit_but_not_the_reverse() {
+        assert_eq!(VariableNameFormat::SnakeCase.convert("value2d"), "value_2d");
+    }
+
+    #[test]
+    fn collapses_separator_runs_and_ignores_leading_and_trailing_ones() {
+        assert_eq!(VariableNameFormat::SnakeCase.convert("__foo--bar  baz__"), "foo_bar_baz");
+    }
+
+    #[test]
+    fn treats_an_all_uppercase_identifier_as_a_single_word() {
+        assert_eq!(VariableNameFormat::SnakeCase.convert("FOO"), "foo");
+        assert_eq!(VariableNameFormat::PascalCase.convert("FOO"), "Foo");
     }
 }
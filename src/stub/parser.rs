@@ -2,211 +2,465 @@ use regex::Regex;
 
 use super::{T, Cmd, Var, Stub, InputComment};
 
+/// A byte range into the original generator text, used to point diagnostics
+/// at the exact token that caused them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TokenKind {
+    Word,
+    Newline,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    kind: TokenKind,
+    text: &'a str,
+    span: Span,
+}
+
+/// Splits a generator into `Word`/`Newline` tokens with byte spans, classifying
+/// runs of spaces and tabs as separators rather than splitting on a single
+/// literal `" "` like the old tokenizer did.
+struct Lexer<'a> {
+    source: &'a str,
+    pos: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(source: &'a str) -> Self {
+        Self { source, pos: 0 }
+    }
+}
 
-pub fn parse_generator_stub(generator: String) -> Stub {
-    let generator = generator.replace("\n", " \n ").replace("\n  \n", "\n \n");
-    let stream = generator.split(" ");
-    Parser::new(stream).parse()
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let bytes = self.source.as_bytes();
+
+        while matches!(bytes.get(self.pos), Some(b' ' | b'\t')) {
+            self.pos += 1;
+        }
+
+        let start = self.pos;
+
+        match bytes.get(self.pos) {
+            None => None,
+            Some(b'\n') => {
+                self.pos += 1;
+                Some(Token { kind: TokenKind::Newline, text: "\n", span: Span::new(start, self.pos) })
+            }
+            Some(_) => {
+                while matches!(bytes.get(self.pos), Some(b) if !matches!(b, b' ' | b'\t' | b'\n')) {
+                    self.pos += 1;
+                }
+                Some(Token { kind: TokenKind::Word, text: &self.source[start..self.pos], span: Span::new(start, self.pos) })
+            }
+        }
+    }
 }
 
-struct Parser<StreamType: Iterator> {
-    stream: StreamType,
+/// A diagnostic produced while parsing a generator stub. `span` always points
+/// into the original, unmodified generator text.
+#[derive(Debug, Clone)]
+pub struct StubError {
+    pub span: Span,
+    pub message: String,
 }
 
-impl<'a, I: Iterator<Item = &'a str>> Parser<I> {
-    fn new(stream: I) -> Self {
-        Self { stream }
+impl StubError {
+    fn new(span: Span, message: impl Into<String>) -> Self {
+        Self { span, message: message.into() }
     }
+}
+
+/// Renders `error` as a caret diagnostic underlining the offending span within
+/// the line it occurred on, e.g.:
+///
+/// ```text
+/// error: expected a `name:type` pair, found `foo`
+///   | read foo N:int
+///   |      ^^^
+/// ```
+pub fn highlight_error(source: &str, error: &StubError) -> String {
+    let line_start = source[..error.span.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[error.span.end.min(source.len())..]
+        .find('\n')
+        .map_or(source.len(), |i| error.span.end + i);
+    let line = &source[line_start..line_end];
+
+    let column = error.span.start - line_start;
+    let underline_width = (error.span.end - error.span.start).max(1);
+
+    format!(
+        "error: {}\n  | {}\n  | {}{}",
+        error.message,
+        line,
+        " ".repeat(column),
+        "^".repeat(underline_width),
+    )
+}
 
-    fn parse(&mut self) -> Stub {
+pub fn parse_generator_stub(generator: &str) -> Result<Stub, Vec<StubError>> {
+    Parser::new(generator).parse()
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    tokens: std::iter::Peekable<Lexer<'a>>,
+    errors: Vec<StubError>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a str) -> Self {
+        Self { source, tokens: Lexer::new(source).peekable(), errors: Vec::new() }
+    }
+
+    fn parse(&mut self) -> Result<Stub, Vec<StubError>> {
         let mut stub = Stub::new();
 
-        while let Some(token) = self.stream.next() {
-            match token {
-                "read" => stub.commands.push(self.parse_read()),
-                "write" => stub.commands.push(self.parse_write()),
-                "loop" => stub.commands.push(self.parse_loop()),
-                "loopline" => stub.commands.push(self.parse_loopline()),
-                "OUTPUT" => stub.output_comment = self.parse_output_comment(),
-                "INPUT" => stub.input_comments.append(&mut self.parse_input_comment()),
-                "STATEMENT" => stub.statement = self.parse_statement(),
-                "\n" | "" => continue,
-                thing => panic!("Error parsing stub generator: {}", thing),
+        while let Some(token) = self.tokens.next() {
+            let result = match token.text {
+                "read" => self.parse_read().map(Some),
+                "write" => self.parse_write().map(Some),
+                "loop" => self.parse_loop().map(Some),
+                "loopline" => self.parse_loopline().map(Some),
+                "OUTPUT" => { self.record(self.parse_output_comment(), |s, c| s.output_comment = c, &mut stub); continue },
+                "INPUT" => { self.record(self.parse_input_comment(), |s, mut c| s.input_comments.append(&mut c), &mut stub); continue },
+                "STATEMENT" => { self.record(self.parse_statement(), |s, c| s.statement = c, &mut stub); continue },
+                "\n" => continue,
+                thing => Err(StubError::new(token.span, format!("unexpected token `{thing}`"))),
             };
+
+            match result {
+                Ok(Some(cmd)) => stub.commands.push(cmd),
+                Ok(None) => {}
+                Err(error) => self.errors.push(error),
+            }
         }
 
-        stub
+        if self.errors.is_empty() {
+            Ok(stub)
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
     }
 
-    fn parse_read(&mut self) -> Cmd {
-        Cmd::Read(self.parse_variable_list())
+    /// Runs a sub-parse that produces a piece of stub metadata (rather than a
+    /// `Cmd`), applying it via `apply` on success or recording the error so
+    /// parsing of the rest of the generator can continue.
+    fn record<V>(&mut self, result: Result<V, StubError>, apply: impl FnOnce(&mut Stub, V), stub: &mut Stub) {
+        match result {
+            Ok(value) => apply(stub, value),
+            Err(error) => self.errors.push(error),
+        }
     }
 
-    fn parse_write(&mut self) -> Cmd {
-        let mut output: Vec<String> = Vec::new();
+    fn parse_read(&mut self) -> Result<Cmd, StubError> {
+        Ok(Cmd::Read(self.parse_variable_list()?))
+    }
 
-        while let Some(token) = self.stream.next() {
-            let next_token = match token { 
-                "\n" => {
-                    match self.stream.next() {
-                        Some("\n") | None => break,
-                        Some(str) => format!("\n{}", str),
-                    }
-                }
-                other => String::from(other),
-            };
+    fn parse_write(&mut self) -> Result<Cmd, StubError> {
+        Ok(Cmd::Write(self.parse_text_block()))
+    }
+
+    fn parse_loop(&mut self) -> Result<Cmd, StubError> {
+        let count_token = self.tokens.next()
+            .filter(|t| t.kind == TokenKind::Word)
+            .ok_or_else(|| StubError::new(self.eof_span(), "loop stub not provided with a loop count"))?;
 
-            output.push(next_token);
-        };
+        let command = self.parse_loop_body()?;
 
-        Cmd::Write(output.join(" "))
+        Ok(Cmd::Loop { count: count_token.text.to_string(), command })
+    }
+
+    /// Parses the body of a `loop`. The baseline single-statement form
+    /// (`loop N read x:int`) still works unchanged: a `read` ends at the end
+    /// of its line and a `write` at the next blank line, as at top level.
+    /// Opening a `{` immediately after the count instead opts into a
+    /// multi-statement, brace-delimited block (`loop N {`, then one
+    /// statement per line, then `}`), which may itself nest further
+    /// `loop`/`loopline` commands. Inside a block a `write` still runs to the
+    /// next blank line, so a `}` on the line right after it becomes part of
+    /// its text.
+    fn parse_loop_body(&mut self) -> Result<Vec<Cmd>, StubError> {
+        let first = self.tokens.next()
+            .ok_or_else(|| StubError::new(self.eof_span(), "loop with no body in stub generator"))?;
+
+        if first.text == "{" {
+            self.parse_loop_block()
+        } else {
+            Ok(vec![self.parse_loop_statement(first)?])
+        }
     }
 
-    fn parse_loop(&mut self) -> Cmd {
-        let count = match self.stream.next() {
-            Some("\n") | None => panic!("Loop stub not provided with loop count"),
-            Some(other) => String::from(other),
-        };
+    /// Parses exactly one `read`/`write`/`loop`/`loopline` command, given its
+    /// already-consumed leading token. Shared by the single-statement loop
+    /// body and the inside of a `{ ... }` block.
+    fn parse_loop_statement(&mut self, token: Token<'a>) -> Result<Cmd, StubError> {
+        match token.text {
+            "read" => self.parse_read(),
+            "write" => self.parse_write(),
+            "loop" => self.parse_loop(),
+            "loopline" => self.parse_loopline(),
+            other => Err(StubError::new(token.span, format!("expected a loop body command, found `{other}`"))),
+        }
+    }
 
-        let command = Box::new(self.parse_read_or_write());
+    /// Parses the inside of a `{ ... }` loop block: one or more statements,
+    /// terminated by a standalone `}` token. An empty block is an error.
+    fn parse_loop_block(&mut self) -> Result<Vec<Cmd>, StubError> {
+        let mut commands = Vec::new();
+
+        while let Some(token) = self.tokens.next() {
+            match token.kind {
+                TokenKind::Newline => continue,
+                TokenKind::Word if token.text == "}" => {
+                    return if commands.is_empty() {
+                        Err(StubError::new(token.span, "loop block has no body in stub generator"))
+                    } else {
+                        Ok(commands)
+                    };
+                }
+                TokenKind::Word => commands.push(self.parse_loop_statement(token)?),
+            }
+        }
 
-        Cmd::Loop { count, command }
+        Err(StubError::new(self.eof_span(), "loop block not terminated with `}`"))
     }
 
-    fn parse_loopline(&mut self) -> Cmd {
-        let object = match self.stream.next() {
-            Some("\n") | None => panic!("Loopline stub not provided with identifier to loop through"),
-            Some(other) => String::from(other),
-        };
+    fn parse_loopline(&mut self) -> Result<Cmd, StubError> {
+        let object_token = self.tokens.next()
+            .filter(|t| t.kind == TokenKind::Word)
+            .ok_or_else(|| StubError::new(self.eof_span(), "loopline stub not provided with an identifier to loop through"))?;
 
-        let variables = self.parse_variable_list();
+        let variables = self.parse_variable_list()?;
 
-        Cmd::LoopLine { object, variables }
+        Ok(Cmd::LoopLine { object: object_token.text.to_string(), variables })
     }
 
-    fn parse_variable(token: &str) -> Var {
-        let mut iter = token.split(":");
-        let name = String::from(iter.next().unwrap());
-        let var_type = iter.next().expect("Error in stub generator: missing type");
+    fn parse_variable(token: Token<'a>) -> Result<Var, StubError> {
+        let mut iter = token.text.split(":");
+        let name = iter.next().unwrap_or_default();
+        let var_type = iter.next()
+            .ok_or_else(|| StubError::new(token.span, format!("missing type for variable `{name}`")))?;
+
         let length_regex = Regex::new(r"(word|string)\((\d+)\)").unwrap();
-        let length_captures = length_regex.captures(var_type);
+
         match var_type {
-            "int" => Var::new(name, T::Int),
-            "float" => Var::new(name, T::Float),
-            "long" => Var::new(name, T::Long),
-            "bool" => Var::new(name, T::Bool),
+            "int" => Ok(Var::new(name.to_string(), T::Int)),
+            "float" => Ok(Var::new(name.to_string(), T::Float)),
+            "long" => Ok(Var::new(name.to_string(), T::Long)),
+            "bool" => Ok(Var::new(name.to_string(), T::Bool)),
             _ => {
-                let caps = length_captures
-                    .expect(format!(
-                        "Failed to parse variable type for token: {}", &token
-                    ).as_str());
-                let new_type = caps.get(1).unwrap().as_str();
-                let max_length: usize = caps.get(2).unwrap().as_str().parse().unwrap();
+                let caps = length_regex.captures(var_type).ok_or_else(|| {
+                    StubError::new(token.span, format!("could not parse variable type `{var_type}`"))
+                })?;
+                let new_type = &caps[1];
+                let max_length: usize = caps[2].parse().map_err(|_| {
+                    StubError::new(token.span, format!("variable length `{}` is too large", &caps[2]))
+                })?;
+
                 match new_type {
-                    "word" => Var::new_length(name, T::Word, max_length),
-                    "string" => Var::new_length(name, T::String, max_length),
-                    _ => panic!("Unexpected error")
+                    "word" => Ok(Var::new_length(name.to_string(), T::Word, max_length)),
+                    "string" => Ok(Var::new_length(name.to_string(), T::String, max_length)),
+                    _ => unreachable!("length_regex only matches \"word\" or \"string\""),
                 }
             }
         }
     }
 
-    fn parse_variable_list(&mut self) -> Vec<Var> {
+    fn parse_variable_list(&mut self) -> Result<Vec<Var>, StubError> {
         let mut vars = Vec::new();
 
-        while let Some(token) = self.stream.next() {
-            let var: Var = match token {
-                _ if String::from(token).contains(":") => {
-                    Self::parse_variable(token)
-                },
-                "" => continue, // "\n read N:int  \n"
-                "\n" => break,
-                unexp => panic!(
-                    "Error in stub generator (parse_variable_list), found \"{unexp}\""),
-            };
-
-            vars.push(var);
-        };
-
-        vars
-    }
-
-    fn parse_read_or_write(&mut self) -> Cmd {
-        match self.stream.next() {
-            Some("read") => self.parse_read(),
-            Some("write") => self.parse_write(),
-            Some(thing) => panic!("Error parsing loop command in stub generator, got: {}", thing),
-            None => panic!("Loop with no arguments in stub generator"),
+        while let Some(token) = self.tokens.next() {
+            match token.kind {
+                TokenKind::Newline => break,
+                TokenKind::Word if token.text.contains(':') => vars.push(Self::parse_variable(token)?),
+                TokenKind::Word => return Err(StubError::new(token.span, format!("expected a `name:type` pair, found `{}`", token.text))),
+            }
         }
+
+        Ok(vars)
     }
 
-    fn parse_output_comment(&mut self) -> String {
-        self.parse_text_block()
+    fn parse_output_comment(&mut self) -> Result<String, StubError> {
+        Ok(self.parse_text_block())
     }
 
-    fn parse_input_comment(&mut self) -> Vec<InputComment> {
+    fn parse_input_comment(&mut self) -> Result<Vec<InputComment>, StubError> {
         self.skip_to_next_line();
         let mut comments = Vec::new();
 
-        while let Some(token) = self.stream.next() {
-            let comment = match token {
-                "\n" => break,
-                _ => {
-                    match token.strip_suffix(":") {
-                        Some(variable) => InputComment::new(String::from(variable), self.read_to_end_of_line()),
-                        None => { self.skip_to_next_line(); continue },
-                    }
-                },
-            };
+        while let Some(token) = self.tokens.next() {
+            if token.kind == TokenKind::Newline {
+                break;
+            }
 
-            comments.push(comment)
+            match token.text.strip_suffix(":") {
+                Some(variable) => comments.push(InputComment::new(variable.to_string(), self.read_to_end_of_line())),
+                None => self.skip_to_next_line(),
+            }
         }
 
-        comments
+        Ok(comments)
     }
 
-    fn parse_statement(&mut self) -> String {
+    fn parse_statement(&mut self) -> Result<String, StubError> {
         self.skip_to_next_line();
-        self.parse_text_block()
+        Ok(self.parse_text_block())
     }
 
     fn read_to_end_of_line(&mut self) -> String {
-        let mut output = Vec::new();
+        let mut words = Vec::new();
 
-        while let Some(token) = self.stream.next() { 
-            match token {
-                "\n" => break,
-                other => output.push(other),
+        while let Some(token) = self.tokens.next() {
+            if token.kind == TokenKind::Newline {
+                break;
             }
+            words.push(token.text);
         }
 
-        output.join(" ")
+        words.join(" ")
     }
 
     fn skip_to_next_line(&mut self) {
-        while let Some(token) = self.stream.next() { 
-            if token == "\n" { break } 
+        for token in self.tokens.by_ref() {
+            if token.kind == TokenKind::Newline {
+                break;
+            }
         }
     }
 
     fn parse_text_block(&mut self) -> String {
-        let mut output: Vec<String> = Vec::new();
-
-        while let Some(token) = self.stream.next() {
-            let next_token = match token { 
-                "\n" => {
-                    match self.stream.next() {
-                        Some("\n") | None => break,
-                        Some(str) => format!("\n{}", str),
+        let mut lines: Vec<String> = Vec::new();
+        let mut current_line: Vec<&str> = Vec::new();
+
+        while let Some(token) = self.tokens.next() {
+            match token.kind {
+                TokenKind::Newline => {
+                    match self.tokens.peek() {
+                        Some(t) if t.kind == TokenKind::Newline => { self.tokens.next(); break },
+                        None => break,
+                        Some(_) => { lines.push(current_line.join(" ")); current_line.clear(); },
                     }
                 }
-                other => String::from(other),
-            };
+                TokenKind::Word => current_line.push(token.text),
+            }
+        }
+
+        if !current_line.is_empty() {
+            lines.push(current_line.join(" "));
+        }
+
+        lines.join("\n")
+    }
+
+    fn eof_span(&self) -> Span {
+        Span::new(self.source.len(), self.source.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            output.push(next_token);
-        };
+    fn unwrap_single_read(stub: &Stub) -> &Vec<Var> {
+        match &stub.commands[..] {
+            [Cmd::Read(vars)] => vars,
+            other => panic!("expected a single Cmd::Read, got {} commands", other.len()),
+        }
+    }
+
+    #[test]
+    fn parses_a_read_with_whitespace_runs_between_tokens() {
+        let stub = parse_generator_stub("read\tn:int   m:float\n").unwrap();
+        assert_eq!(unwrap_single_read(&stub).len(), 2);
+    }
+
+    #[test]
+    fn collects_multiple_errors_in_one_pass_instead_of_aborting() {
+        let source = "read a:bogus\nfrob\n";
+        let errors = parse_generator_stub(source).unwrap_err();
+
+        assert_eq!(errors.len(), 2);
+        assert_eq!(&source[errors[0].span.start..errors[0].span.end], "a:bogus");
+        assert_eq!(&source[errors[1].span.start..errors[1].span.end], "frob");
+    }
+
+    #[test]
+    fn an_overlong_variable_length_is_a_recoverable_error_not_a_panic() {
+        let source = "read s:string(99999999999999999999)\n";
+        let errors = parse_generator_stub(source).unwrap_err();
+
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.contains("too large"), "message was: {}", errors[0].message);
+    }
+
+    #[test]
+    fn highlight_error_underlines_the_exact_offending_token() {
+        let source = "read a:bogus\n";
+        let errors = parse_generator_stub(source).unwrap_err();
+        let rendered = highlight_error(source, &errors[0]);
+
+        assert!(rendered.contains("read a:bogus"), "rendered: {rendered}");
+        assert!(rendered.contains(&"^".repeat("a:bogus".len())), "rendered: {rendered}");
+    }
 
-        output.join(" ")
+    #[test]
+    fn single_statement_loop_does_not_swallow_the_following_command() {
+        let stub = parse_generator_stub("loop n write foo\n\nwrite bar\n").unwrap();
+        assert_eq!(stub.commands.len(), 2);
     }
 
+    #[test]
+    fn single_statement_loop_body_can_itself_be_a_nested_loop() {
+        let stub = parse_generator_stub("loop n loop m read a:int\n").unwrap();
+
+        match &stub.commands[..] {
+            [Cmd::Loop { count, command }] => {
+                assert_eq!(count, "n");
+                match &command[..] {
+                    [Cmd::Loop { count, command }] => {
+                        assert_eq!(count, "m");
+                        assert_eq!(command.len(), 1);
+                    }
+                    other => panic!("expected a nested Cmd::Loop, got {} commands", other.len()),
+                }
+            }
+            other => panic!("expected a single Cmd::Loop, got {} commands", other.len()),
+        }
+    }
+
+    #[test]
+    fn braced_loop_body_supports_multiple_statements() {
+        let source = "loop n {\n read a:int\n read b:int\n}\n";
+        let stub = parse_generator_stub(source).unwrap();
+
+        match &stub.commands[..] {
+            [Cmd::Loop { command, .. }] => assert_eq!(command.len(), 2),
+            other => panic!("expected a single Cmd::Loop, got {} commands", other.len()),
+        }
+    }
+
+    #[test]
+    fn unterminated_braced_loop_is_an_eof_anchored_error() {
+        let source = "loop n {\n read a:int\n";
+        let errors = parse_generator_stub(source).unwrap_err();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].span.start, source.len());
+        assert!(errors[0].message.contains('}'), "message was: {}", errors[0].message);
+    }
 }
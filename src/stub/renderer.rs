@@ -1,35 +1,36 @@
-use tera::{Tera, Context};
+use tera::Context;
 use tera_text_filters::{snake_case, camel_case, mixed_case};
 
-use crate::programming_language::{ProgrammingLanguage, VariableNameFormat};
+use crate::stub::language::{Language, VariableNameFormat};
 use super::parser::{Cmd, Stub, VariableCommand};
 
 mod types;
 use types::ReadData;
 
-pub fn render_stub(lang: ProgrammingLanguage, stub: Stub) -> String {
+pub fn render_stub(lang: Language, stub: Stub) -> String {
     let rend = Renderer::new(lang, stub);
     rend.render()
 }
 
 struct Renderer {
-    tera: Tera,
-    lang: ProgrammingLanguage,
+    lang: Language,
     stub: Stub,
 }
 
 impl Renderer {
-    fn new(lang: ProgrammingLanguage, stub: Stub) -> Self {
-        let mut tera = Tera::new(&lang.template_glob())
-            .expect("There are no templates for this language");
+    fn new(mut lang: Language, stub: Stub) -> Self {
+        // `lang.tera` is already built (from the embedded templates, or from
+        // `COCTUS_LANG_DIR` when the language was resolved from there), so we
+        // render against it directly instead of re-globbing templates from a
+        // hardcoded path.
         let case_fn = match lang.variable_format {
             // camel_case types don't match tera-text-filters conventions. To fix.
             VariableNameFormat::SnakeCase => snake_case,
             VariableNameFormat::CamelCase => mixed_case,
             VariableNameFormat::PascalCase => camel_case,
         };
-        tera.register_filter("case", case_fn);
-        Self { lang, tera, stub }
+        lang.tera.register_filter("case", case_fn);
+        Self { lang, stub }
     }
 
     fn render(&self) -> String {
@@ -44,7 +45,7 @@ impl Renderer {
 
         context.insert("commands", &commands);
 
-        self.tera.render(&format!("main.{}.jinja", self.lang.source_file_ext), &context)
+        self.lang.tera.render(&format!("main.{}.jinja", self.lang.source_file_ext), &context)
             .expect("Failed to render template for stub")
     }
 
@@ -60,7 +61,7 @@ impl Renderer {
     fn render_write(&self, message: &String) -> String {
         let mut context = Context::new();
         context.insert("messages", &message.lines().collect::<Vec<&str>>());
-        self.tera.render(&self.template_path("write"), &context)
+        self.lang.tera.render(&self.template_path("write"), &context)
             .expect("Could not find write template")
     }
 
@@ -69,16 +70,18 @@ impl Renderer {
         let mut context = Context::new();
         context.insert("vars", &read_data);
         context.insert("type_tokens", &self.lang.type_tokens);
-        self.tera.render(&self.template_path("read"), &context)
+        self.lang.tera.render(&self.template_path("read"), &context)
             .expect("Could not find read template").trim_end().to_owned()
     }
 
-    fn render_loop(&self, count: &String, cmd: &Box<Cmd>) -> String {
+    fn render_loop(&self, count: &String, commands: &Vec<Cmd>) -> String {
         let mut context = Context::new();
-        let rendered_cmd: Vec<String> = self.render_command(&cmd).lines().map(|s|s.to_owned()).collect();
+        let rendered_cmds: Vec<String> = commands.iter()
+            .flat_map(|cmd| self.render_command(cmd).lines().map(str::to_owned).collect::<Vec<_>>())
+            .collect();
         context.insert("count", &count);
-        context.insert("inner", &rendered_cmd);
-        self.tera.render(&self.template_path("loop"), &context)
+        context.insert("inner", &rendered_cmds);
+        self.lang.tera.render(&self.template_path("loop"), &context)
             .expect("Could not find loop template")
     }
 
@@ -92,7 +95,7 @@ impl Renderer {
         context.insert("object", &object);
         context.insert("vars", &read_data);
         context.insert("type_tokens", &self.lang.type_tokens);
-        self.tera.render(&self.template_path("loopline"), &context)
+        self.lang.tera.render(&self.template_path("loopline"), &context)
             .expect("Could not find read template")
     }
 }